@@ -1,19 +1,33 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use proc_macro2::Span;
+use std::collections::HashSet;
 
-use luaparse::ast::{ForStat, FunctionBody, FunctionDeclarationStat, Name, Statement, Var};
+use luaparse::ast::{
+    Expr, ForStat, FunctionArgs, FunctionBody, FunctionCall, FunctionCallee,
+    FunctionDeclarationStat, FunctionName, Name, PrefixExpr, Statement, TableConstructor,
+    TableKey, Var, VarField,
+};
 use luaparse::error::Error as LError;
 use luaparse::HasSpan;
 use quote::quote;
-use rlua::Lua;
-use syn::Error as SynError;
+use rlua::{Context, Lua, Value, Variadic};
+use syn::{Error as SynError, LitStr};
 
+// Validates and re-emits a UDF, as `(source, functions)` where `functions` is
+// a slice of `(name, arity)` pairs for every exported (non-local, top-level)
+// function, so callers can register the module and invoke its functions by
+// name without hand-duplicating the names Aerospike expects.
 #[proc_macro]
 pub fn define(input: TokenStream) -> TokenStream {
     let s = input.to_string();
     let mut lua_err = None;
     Lua::new().context(|lua| {
+        if let Err(err) = install_mock_globals(lua) {
+            lua_err = Some(err.to_string());
+            return;
+        }
+
         let chunk = lua.load(&s);
         let r = chunk.exec();
         if let Err(err) = r {
@@ -21,11 +35,11 @@ pub fn define(input: TokenStream) -> TokenStream {
         }
     });
 
-    let errors = validate_aerospike(&s);
+    let Validated { errors, functions } = validate_aerospike(&s);
     let mut syn_errs = vec![];
 
-    for e in errors {
-        syn_errs.push(SynError::new(Span::call_site(), &e));
+    for (msg, offset) in errors {
+        syn_errs.push(SynError::new(span_for_offset(&input, &s, offset), &msg));
     }
 
     if let Some(f) = syn_errs.first() {
@@ -45,20 +59,273 @@ pub fn define(input: TokenStream) -> TokenStream {
             .into();
     }
 
-    let tokens = quote! {#s};
+    let names = functions.iter().map(|(name, _)| name.as_str());
+    let arities = functions.iter().map(|(_, arity)| *arity);
+
+    let tokens = quote! {
+        (#s, &[#((#names, #arities)),*] as &[(&str, usize)])
+    };
 
     tokens.into()
 }
 
-fn validate_aerospike(s: &str) -> Vec<String> {
-    let mut errs = vec![];
+// Like `define!`, but for a UDF kept in its own `.lua` file: `define_file!("udfs/foo.lua")`.
+// The path is resolved relative to the crate root (`CARGO_MANIFEST_DIR`), and
+// errors are reported with that file's line/column baked into the message,
+// since a stable proc macro can't point a diagnostic span at a file other
+// than the one it was invoked from. Emitting `include_str!` with the
+// resolved path (rather than splicing the contents we read for validation
+// directly into the token stream) also registers the file as a dependency,
+// so edits to it trigger recompilation.
+#[proc_macro]
+pub fn define_file(input: TokenStream) -> TokenStream {
+    let path_lit = match syn::parse::<LitStr>(input) {
+        Ok(lit) => lit,
+        Err(err) => return err.into_compile_error().into(),
+    };
+    let rel_path = path_lit.value();
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let full_path = std::path::Path::new(&manifest_dir).join(&rel_path);
+    let full_path = full_path.to_string_lossy().into_owned();
+
+    let s = match std::fs::read_to_string(&full_path) {
+        Ok(s) => s,
+        Err(err) => {
+            return SynError::new(
+                path_lit.span(),
+                format!("failed to read `{}`: {}", rel_path, err),
+            )
+            .into_compile_error()
+            .into();
+        }
+    };
+
+    let mut lua_err = None;
+    Lua::new().context(|lua| {
+        if let Err(err) = install_mock_globals(lua) {
+            lua_err = Some(err.to_string());
+            return;
+        }
+
+        let chunk = lua.load(&s);
+        let r = chunk.exec();
+        if let Err(err) = r {
+            lua_err = Some(err.to_string());
+        }
+    });
+
+    let Validated { errors, functions } = validate_aerospike(&s);
+    let mut syn_errs = vec![];
+
+    for (msg, offset) in errors {
+        let (line, column) = line_col(&s, offset);
+        syn_errs.push(SynError::new(
+            path_lit.span(),
+            format!("{}:{}:{}: {}", rel_path, line, column, msg),
+        ));
+    }
+
+    if let Some(f) = syn_errs.first() {
+        let mut f_err = f.clone();
+
+        if syn_errs.len() > 1 {
+            for e in &syn_errs[1..] {
+                f_err.combine(e.clone());
+            }
+        }
+        return f_err.into_compile_error().into();
+    }
+
+    if let Some(err) = lua_err {
+        return SynError::new(path_lit.span(), format!("{}: {}", rel_path, err))
+            .into_compile_error()
+            .into();
+    }
+
+    let names = functions.iter().map(|(name, _)| name.as_str());
+    let arities = functions.iter().map(|(_, arity)| *arity);
+
+    let tokens = quote! {
+        (include_str!(#full_path), &[#((#names, #arities)),*] as &[(&str, usize)])
+    };
+
+    tokens.into()
+}
+
+// 1-based line/column of a byte offset into `s`, for diagnostics that can't
+// carry a span into the file `s` came from.
+fn line_col(s: &str, offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut column = 1;
+    for ch in s[..offset.min(s.len())].chars() {
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+// The `aerospike`, `record`, `map`, etc. globals a real UDF runs against don't
+// exist in a bare rlua state. Stub each one out as a table whose every field
+// is a no-op function, so `chunk.exec()` can still catch genuine mistakes in
+// module-load-time code (nil arithmetic, arity errors, ...) without flagging
+// the legitimate use of the Aerospike API surface as an error.
+fn install_mock_globals(lua: Context) -> rlua::Result<()> {
+    let globals = lua.globals();
+    for name in AEROSPIKE_NAMES {
+        globals.set(name, mock_module(lua)?)?;
+    }
+    Ok(())
+}
+
+fn mock_module(lua: Context) -> rlua::Result<rlua::Table> {
+    let table = lua.create_table()?;
+    let metatable = lua.create_table()?;
+    metatable.set(
+        "__index",
+        lua.create_function(|lua, (_table, _key): (rlua::Table, String)| {
+            lua.create_function(|_, _: Variadic<Value>| Ok(Value::Nil))
+        })?,
+    )?;
+    table.set_metatable(Some(metatable));
+    Ok(table)
+}
+
+// Maps a byte offset into `s` (which is `input.to_string()`) back onto a
+// `proc_macro::Span`. `TokenStream::to_string()` only inserts whitespace
+// between tokens where the original source had any (collapsed to a single
+// space), and none between tokens that were adjacent (`foo(a,b)`), so the
+// gap between two tokens' text can't be predicted from the tokens alone.
+// Instead of reconstructing that spacing, this walks `input` in the same
+// left-to-right order `to_string()` printed it in and, for each token,
+// searches forward from the end of the previous one for its own rendered
+// text, so it lands on the right occurrence regardless of how much (if any)
+// whitespace separates the two.
+fn span_for_offset(input: &TokenStream, s: &str, offset: usize) -> Span {
+    let mut cursor = 0usize;
+    find_span(input.clone(), s, offset, &mut cursor).unwrap_or_else(Span::call_site)
+}
+
+fn find_span(stream: TokenStream, s: &str, offset: usize, cursor: &mut usize) -> Option<Span> {
+    for tt in stream {
+        if let proc_macro::TokenTree::Group(group) = &tt {
+            let (open, close) = delimiter_strs(group.delimiter());
+            advance_past(s, cursor, open);
+            if let Some(span) = find_span(group.stream(), s, offset, cursor) {
+                return Some(span);
+            }
+            advance_past(s, cursor, close);
+        } else {
+            let text = tt.to_string();
+            if let Some(rel) = s[*cursor..].find(text.as_str()) {
+                let start = *cursor + rel;
+                let end = start + text.len();
+                *cursor = end;
+                if offset >= start && offset < end {
+                    return Some(tt.span().into());
+                }
+            }
+        }
+    }
+    None
+}
+
+// Advances `cursor` past the next occurrence of `delim` (a delimiter's own
+// text; `find_span` calls this with `cursor` just before that delimiter, so
+// the first match is the delimiter itself even though a literal token such
+// as `"("` can contain the same text), or leaves it alone if `delim` is
+// empty (an invisible group).
+fn advance_past(s: &str, cursor: &mut usize, delim: &str) {
+    if delim.is_empty() {
+        return;
+    }
+    if let Some(rel) = s[*cursor..].find(delim) {
+        *cursor += rel + delim.len();
+    }
+}
+
+fn delimiter_strs(delimiter: proc_macro::Delimiter) -> (&'static str, &'static str) {
+    match delimiter {
+        proc_macro::Delimiter::Parenthesis => ("(", ")"),
+        proc_macro::Delimiter::Brace => ("{", "}"),
+        proc_macro::Delimiter::Bracket => ("[", "]"),
+        proc_macro::Delimiter::None => ("", ""),
+    }
+}
+
+// Tracks validation errors alongside the lexical scope stack used to resolve
+// variable reads. A new `HashSet` is pushed for every function body and every
+// `if`/`while`/`for`/`repeat` block, and popped once that block is done being
+// walked; a name resolves if it's present in that scope or any enclosing one.
+struct Ctx {
+    errors: Vec<(String, usize)>,
+    scopes: Vec<HashSet<String>>,
+}
+
+impl Ctx {
+    fn error(&mut self, message: String, offset: usize) {
+        self.errors.push((message, offset));
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashSet::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string());
+        }
+    }
+
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes.iter().rev().any(|scope| scope.contains(name))
+    }
+}
+
+struct Validated {
+    errors: Vec<(String, usize)>,
+    // (name, arity) for every exported top-level function, in source order.
+    functions: Vec<(String, usize)>,
+}
+
+fn validate_aerospike(s: &str) -> Validated {
     match luaparse::parse(s) {
         Ok(block) => {
-            loop_statements(&block.statements, &mut errs, true);
+            let mut ctx = Ctx {
+                errors: vec![],
+                scopes: vec![HashSet::new()],
+            };
+            let mut functions = vec![];
+            // Top-level `function foo() ... end` declarations are visible to
+            // every sibling, even ones defined earlier in the file, so they
+            // need to be in scope before any of them are walked; that same
+            // pass also collects them as the module's exported functions.
+            for statement in &block.statements {
+                if let Statement::FunctionDeclaration(FunctionDeclarationStat::Nonlocal {
+                    name: FunctionName::PlainName(name),
+                    body,
+                    ..
+                }) = statement
+                {
+                    ctx.declare(&name.to_string());
+                    functions.push((name.to_string(), body.params.list.pairs.len()));
+                }
+            }
+            loop_statements(&block.statements, &mut ctx, true);
+            Validated {
+                errors: ctx.errors,
+                functions,
+            }
         }
         Err(e) => panic!("{:#}", LError::new(e.span(), e).with_buffer(s)),
     }
-    errs
 }
 
 const AEROSPIKE_NAMES: [&str; 9] = [
@@ -82,34 +349,175 @@ fn is_reserved(s: &str) -> bool {
     false
 }
 
-fn validate_func(body: &FunctionBody, errors: &mut Vec<String>, is_global: bool) {
-    let names = body.params.list.pairs.iter().map(|a| a.0.clone()).collect();
-    validate_names(&names, errors, true);
-    loop_statements(&body.block.statements, errors, is_global);
+// Modules and functions the Aerospike Lua sandbox doesn't expose. `os`, `io`,
+// `debug` and `package` aren't loaded into the UDF environment, and the
+// loaders (`require`/`dofile`/`loadstring`) and `collectgarbage` are stripped
+// out alongside them.
+const FORBIDDEN_GLOBALS: [&str; 8] = [
+    "os",
+    "io",
+    "debug",
+    "package",
+    "require",
+    "dofile",
+    "loadstring",
+    "collectgarbage",
+];
+
+fn is_forbidden(s: &str) -> bool {
+    for forbidden in FORBIDDEN_GLOBALS {
+        if forbidden == s {
+            return true;
+        }
+    }
+    false
+}
+
+// Finds the outermost name a chain of `.`/`[]` accesses or a call is rooted
+// at, so `os.exit()` and `os["exit"]()` both resolve to `os`.
+fn root_name(prefix: &PrefixExpr) -> Option<String> {
+    match prefix {
+        PrefixExpr::Var(Var::Name(n)) => Some(n.to_string()),
+        PrefixExpr::Var(Var::Field(prefix, _)) => root_name(prefix),
+        PrefixExpr::Call(_) | PrefixExpr::Parenthesized(_) => None,
+    }
+}
+
+fn walk_args(args: &FunctionArgs, ctx: &mut Ctx) {
+    match args {
+        FunctionArgs::ParenthesizedList(list) => {
+            for pair in &list.list.pairs {
+                walk_expr(&pair.0, ctx);
+            }
+        }
+        FunctionArgs::TableConstructor(table) => walk_table(table, ctx),
+        FunctionArgs::StringLit(_) => {}
+    }
+}
+
+fn walk_table(table: &TableConstructor, ctx: &mut Ctx) {
+    for field in &table.fields {
+        if let Some((TableKey::Expr { key, .. }, _)) = &field.key {
+            walk_expr(key, ctx);
+        }
+        walk_expr(&field.value, ctx);
+    }
+}
+
+// Checks `callee`'s root against the denylist and, only when it isn't
+// forbidden there, recurses into it — a chain rooted at a forbidden global
+// (`os.time.foo()`, `os:execute()`) is one mistake and gets one diagnostic,
+// not one per member access on the way down.
+fn walk_callee(callee: &PrefixExpr, ctx: &mut Ctx) {
+    match root_name(callee) {
+        Some(name) if is_forbidden(&name) => {
+            ctx.error(
+                format!("`{}` is not available in the Aerospike UDF sandbox", name),
+                callee.span().start.byte,
+            );
+        }
+        _ => walk_prefix(callee, ctx),
+    }
+}
+
+fn walk_call(call: &FunctionCall, ctx: &mut Ctx) {
+    match &call.callee {
+        FunctionCallee::Expr(callee) => walk_callee(callee, ctx),
+        FunctionCallee::Method { object, .. } => walk_callee(object, ctx),
+    }
+    walk_args(&call.args, ctx);
+}
+
+fn walk_var(var: &Var, ctx: &mut Ctx) {
+    match var {
+        Var::Name(n) => {
+            let name = n.to_string();
+            if !is_reserved(&name) && !ctx.is_bound(&name) {
+                ctx.error(
+                    format!("reference to global variable: `{}`", name),
+                    n.span().start.byte,
+                );
+            }
+        }
+        Var::Field(prefix, field) => {
+            match root_name(prefix) {
+                Some(root) if is_forbidden(&root) => {
+                    ctx.error(
+                        format!("`{}` is not available in the Aerospike UDF sandbox", root),
+                        field.span().start.byte,
+                    );
+                }
+                _ => walk_prefix(prefix, ctx),
+            }
+            if let VarField::Expr { key, .. } = field {
+                walk_expr(key, ctx);
+            }
+        }
+    }
+}
+
+fn walk_prefix(prefix: &PrefixExpr, ctx: &mut Ctx) {
+    match prefix {
+        PrefixExpr::Var(var) => walk_var(var, ctx),
+        PrefixExpr::Call(call) => walk_call(call, ctx),
+        PrefixExpr::Parenthesized(paren) => walk_expr(&paren.expr, ctx),
+    }
+}
+
+fn walk_expr(expr: &Expr, ctx: &mut Ctx) {
+    match expr {
+        Expr::Prefix(prefix) => walk_prefix(prefix, ctx),
+        Expr::BinOp(b) => {
+            walk_expr(&b.left, ctx);
+            walk_expr(&b.right, ctx);
+        }
+        Expr::UnOp(u) => walk_expr(&u.right, ctx),
+        Expr::Function(f) => validate_func(&f.body, ctx, false),
+        Expr::TableConstructor(table) => walk_table(table, ctx),
+        _ => {}
+    }
 }
 
-fn loop_statements(stmts: &Vec<Statement>, errors: &mut Vec<String>, is_global: bool) {
+fn validate_func(body: &FunctionBody, ctx: &mut Ctx, is_global: bool) {
+    let names: Vec<Name> = body.params.list.pairs.iter().map(|a| a.0.clone()).collect();
+    validate_names(&names, ctx, true);
+    ctx.push_scope();
+    for name in &names {
+        ctx.declare(&name.to_string());
+    }
+    loop_statements(&body.block.statements, ctx, is_global);
+    ctx.pop_scope();
+}
+
+fn loop_statements(stmts: &Vec<Statement>, ctx: &mut Ctx, is_global: bool) {
     for statement in stmts {
-        recurse(statement, errors, is_global);
+        recurse(statement, ctx, is_global);
     }
 }
 
-fn validate_names(names: &Vec<Name>, errors: &mut Vec<String>, allow_vars: bool) {
+fn validate_names(names: &Vec<Name>, ctx: &mut Ctx, allow_vars: bool) {
     for param in names {
         let name = param.to_string();
+        let offset = param.span().start.byte;
         if is_reserved(&name) {
-            errors.push(format!(
-                "aerospike reserved identifier: `{}`. consider renaming your variable",
-                name
-            ));
+            ctx.error(
+                format!(
+                    "aerospike reserved identifier: `{}`. consider renaming your variable",
+                    name
+                ),
+                offset,
+            );
         }
         if !allow_vars {
-            errors.push(format!("global variables are not allowed: `{}`", name));
+            ctx.error(
+                format!("global variables are not allowed: `{}`", name),
+                offset,
+            );
         }
     }
 }
 
-fn recurse(stmt: &Statement, errors: &mut Vec<String>, mut is_global: bool) {
+fn recurse(stmt: &Statement, ctx: &mut Ctx, mut is_global: bool) {
     let mut allow_vars = true;
     if is_global {
         is_global = false;
@@ -117,62 +525,216 @@ fn recurse(stmt: &Statement, errors: &mut Vec<String>, mut is_global: bool) {
     }
     match stmt {
         Statement::FunctionDeclaration(func) => match func {
-            FunctionDeclarationStat::Local { body, .. } => {
-                validate_func(&body, errors, is_global);
+            FunctionDeclarationStat::Local { name, body, .. } => {
+                // `local function f` is sugar for declaring the local before
+                // assigning it, so `f` can see itself for recursive calls.
+                ctx.declare(&name.to_string());
+                validate_func(body, ctx, is_global);
             }
             FunctionDeclarationStat::Nonlocal { body, .. } => {
-                validate_func(&body, errors, is_global);
+                validate_func(body, ctx, is_global);
             }
         },
         Statement::LocalDeclaration(ld) => {
-            let names = ld.names.pairs.iter().map(|a| a.0.clone()).collect();
-            validate_names(&names, errors, allow_vars);
+            let names: Vec<Name> = ld.names.pairs.iter().map(|a| a.0.clone()).collect();
+            if let Some(def) = &ld.definition {
+                for pair in &def.exprs.pairs {
+                    walk_expr(&pair.0, ctx);
+                }
+            }
+            validate_names(&names, ctx, allow_vars);
+            for name in &names {
+                ctx.declare(&name.to_string());
+            }
         }
         Statement::Assignment(ass) => {
             let names = ass
                 .vars
                 .pairs
                 .iter()
-                .filter(|a| {
-                    if let Var::Name(_n) = &a.0 {
-                        return true;
-                    }
-                    return false;
-                })
-                .map(|a| {
-                    if let Var::Name(n) = &a.0 {
-                        return n.clone();
-                    }
-                    panic!("impossible")
+                .filter_map(|a| match &a.0 {
+                    Var::Name(n) => Some(n.clone()),
+                    _ => None,
                 })
                 .collect();
 
-            validate_names(&names, errors, allow_vars);
+            // `os.exit = nil` / `t[undeclared] = 1`: member and index targets
+            // aren't new global bindings, but they can still reach a
+            // forbidden module or read an undeclared variable in the index
+            // expression, so they go through the same checks as a read.
+            for pair in &ass.vars.pairs {
+                if !matches!(pair.0, Var::Name(_)) {
+                    walk_var(&pair.0, ctx);
+                }
+            }
+
+            validate_names(&names, ctx, allow_vars);
+            for pair in &ass.exprs.pairs {
+                walk_expr(&pair.0, ctx);
+            }
+        }
+        Statement::FunctionCall(call) => {
+            walk_call(call, ctx);
         }
         Statement::If(i) => {
-            loop_statements(&i.block.statements, errors, is_global);
+            walk_expr(&i.condition, ctx);
+            ctx.push_scope();
+            loop_statements(&i.block.statements, ctx, is_global);
+            ctx.pop_scope();
             if let Some(el) = &i.else_ {
-                loop_statements(&el.block.statements, errors, is_global);
+                ctx.push_scope();
+                loop_statements(&el.block.statements, ctx, is_global);
+                ctx.pop_scope();
             }
 
             for elseif in &i.elseifs {
-                loop_statements(&elseif.block.statements, errors, is_global);
+                walk_expr(&elseif.condition, ctx);
+                ctx.push_scope();
+                loop_statements(&elseif.block.statements, ctx, is_global);
+                ctx.pop_scope();
             }
         }
         Statement::While(wl) => {
-            loop_statements(&wl.block.statements, errors, is_global);
+            walk_expr(&wl.condition, ctx);
+            ctx.push_scope();
+            loop_statements(&wl.block.statements, ctx, is_global);
+            ctx.pop_scope();
         }
         Statement::For(f) => match f {
             ForStat::Generic(fg) => {
-                loop_statements(&fg.block.statements, errors, is_global);
+                for pair in &fg.exprs.pairs {
+                    walk_expr(&pair.0, ctx);
+                }
+                ctx.push_scope();
+                for pair in &fg.names.pairs {
+                    ctx.declare(&pair.0.to_string());
+                }
+                loop_statements(&fg.block.statements, ctx, is_global);
+                ctx.pop_scope();
             }
             ForStat::Numerical(n) => {
-                loop_statements(&n.block.statements, errors, is_global);
+                walk_expr(&n.from, ctx);
+                walk_expr(&n.to, ctx);
+                if let Some((_, step)) = &n.step {
+                    walk_expr(step, ctx);
+                }
+                ctx.push_scope();
+                ctx.declare(&n.name.to_string());
+                loop_statements(&n.block.statements, ctx, is_global);
+                ctx.pop_scope();
             }
         },
         Statement::Repeat(rp) => {
-            loop_statements(&rp.block.statements, errors, is_global);
+            ctx.push_scope();
+            loop_statements(&rp.block.statements, ctx, is_global);
+            // `until` can see locals declared in the block, so the condition
+            // is walked before the scope is popped.
+            walk_expr(&rp.condition, ctx);
+            ctx.pop_scope();
+        }
+        Statement::Return(r) => {
+            for pair in &r.exprs.pairs {
+                walk_expr(&pair.0, ctx);
+            }
+        }
+        Statement::Block(b) => {
+            ctx.push_scope();
+            loop_statements(&b.block.statements, ctx, is_global);
+            ctx.pop_scope();
         }
         _ => {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(src: &str) -> Vec<String> {
+        validate_aerospike(src)
+            .errors
+            .into_iter()
+            .map(|(msg, _)| msg)
+            .collect()
+    }
+
+    #[test]
+    fn reserved_identifier_is_rejected() {
+        let errs = errors("function f() local record = 1 end");
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("aerospike reserved identifier"), "{errs:?}");
+    }
+
+    #[test]
+    fn forbidden_library_call_is_rejected() {
+        let errs = errors("function f() local x = os.exit() end");
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("`os` is not available"), "{errs:?}");
+    }
+
+    #[test]
+    fn forbidden_library_call_through_method_syntax_is_rejected() {
+        let errs = errors("function f() local x = os:exit() end");
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("`os` is not available"), "{errs:?}");
+    }
+
+    #[test]
+    fn global_read_is_rejected() {
+        let errs = errors("function f() local x = bar + 1 end");
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("reference to global variable: `bar`"), "{errs:?}");
+    }
+
+    #[test]
+    fn global_read_through_return_is_rejected() {
+        let errs = errors("function foo() return bar + 1 end");
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("reference to global variable: `bar`"), "{errs:?}");
+    }
+
+    #[test]
+    fn forbidden_library_call_inside_return_is_rejected() {
+        let errs = errors("function foo() return os.exit() end");
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("`os` is not available"), "{errs:?}");
+    }
+
+    #[test]
+    fn forbidden_library_call_inside_do_block_is_rejected() {
+        let errs = errors("function foo() do os.exit() end end");
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].contains("`os` is not available"), "{errs:?}");
+    }
+
+    #[test]
+    fn sibling_functions_can_forward_reference_each_other() {
+        let errs = errors(
+            r#"
+            function first()
+                return second()
+            end
+            function second()
+                return 1
+            end
+            "#,
+        );
+        assert!(errs.is_empty(), "{errs:?}");
+    }
+
+    #[test]
+    fn closures_can_see_enclosing_locals() {
+        let errs = errors(
+            r#"
+            function outer()
+                local x = 1
+                local function inner()
+                    return x
+                end
+                return inner()
+            end
+            "#,
+        );
+        assert!(errs.is_empty(), "{errs:?}");
+    }
+}